@@ -1,27 +1,125 @@
+use clap::{Parser, Subcommand};
 use config::{Config, File, FileFormat};
 use enigo::Enigo;
-use midir::MidiInput;
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use regex::Regex;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
 mod enigo_dsl;
+mod midi;
+mod pulse;
+
+use midi::{MidiEvent, MidiEventType};
+use pulse::{PulseAction, PulseHandle};
+
+/// How often the watcher re-enumerates MIDI ports to detect hot-plug/unplug events.
+const PORT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+const DEFAULT_CONFIG_PATH: &str = "~/.miditokeydaemonrc";
+
+#[derive(Parser, Debug)]
+#[command(name = "miditokeydaemon", version, about = "A MIDI controller daemon for keymaps, commands, PulseAudio and more.")]
+struct Cli {
+    /// Path to the configuration file.
+    #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
+    config: String,
+
+    /// Parse and log which mapping would fire for each message, without acting on it.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum CliCommand {
+    /// List every available MIDI input port and exit.
+    ListPorts,
+}
 
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct Settings {
+    /// A regular expression matched against each candidate port name, e.g. "Launchpad.*".
     pub device_port_name: String,
+    /// A regular expression for the output port to send `feedback` to. Defaults to
+    /// `device_port_name`, since most controllers expose a single bidirectional port.
+    pub feedback_port_name: Option<String>,
     pub midi_mapping: Vec<MidiMap>,
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct MidiMap {
-    pub midi_id: u8,
-    pub note: u8,
+    /// The note or controller number to match. Leave unset for channel-wide events like
+    /// `PitchBend`, where the data byte this would otherwise compare against is part of the
+    /// 14-bit value itself, not a stable identifier.
+    pub note: Option<u8>,
+    pub event_type: Option<MidiEventType>,
+    pub channel: Option<u8>,
     pub keymap: Option<String>,
-    pub velocity: Option<u8>,
+    pub value_range: Option<ValueRange>,
     pub command: Option<String>,
     pub options: Option<MidiMapOptions>,
     pub mouse: Option<String>,
+    pub pulse: Option<PulseAction>,
+    pub feedback: Option<Feedback>,
+}
+
+/// Bytes to send back to the controller when a mapping fires, e.g. to light up a pad or
+/// move a motorized fader. `SysEx` is an escape hatch for raw `F0 ... F7` sequences such as
+/// device-specific LED ring modes.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Feedback {
+    NoteOn {
+        note: u8,
+        velocity: u8,
+        channel: Option<u8>,
+    },
+    ControlChange {
+        controller: u8,
+        value: u8,
+        channel: Option<u8>,
+    },
+    SysEx {
+        bytes: Vec<u8>,
+    },
+}
+
+impl Feedback {
+    /// Renders this feedback action into the raw bytes to send to the MIDI output.
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Feedback::NoteOn {
+                note,
+                velocity,
+                channel,
+            } => vec![0x90 | channel.unwrap_or(0), *note, *velocity],
+            Feedback::ControlChange {
+                controller,
+                value,
+                channel,
+            } => vec![0xB0 | channel.unwrap_or(0), *controller, *value],
+            Feedback::SysEx { bytes } => {
+                let mut message = Vec::with_capacity(bytes.len() + 2);
+                message.push(0xF0);
+                message.extend_from_slice(bytes);
+                message.push(0xF7);
+                message
+            }
+        }
+    }
+}
+
+/// A window a parsed MIDI value must fall in for a mapping to match, e.g. a fader/knob
+/// position or pitch-bend range rather than a single fixed velocity.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct ValueRange {
+    pub min: u16,
+    pub max: u16,
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
@@ -39,57 +137,211 @@ pub struct MidiMapVelocityOptions {
 pub struct VelocityScale {
     pub min: u8,
     pub max: u8,
+    pub curve: Option<VelocityCurve>,
+    pub invert: Option<bool>,
+}
+
+/// A response curve applied to the normalized input (`t = input / 127.0`) before mapping
+/// it into `[min, max]`, for natural-feeling fader/expression-pedal behavior.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VelocityCurve {
+    Linear,
+    Exponential { gamma: f32 },
+    Logarithmic,
+    SCurve,
+}
+
+impl VelocityCurve {
+    /// Shapes a normalized `0.0..=1.0` input according to this curve.
+    fn apply(&self, t: f32) -> f32 {
+        match self {
+            VelocityCurve::Linear => t,
+            VelocityCurve::Exponential { gamma } => t.powf(*gamma),
+            VelocityCurve::Logarithmic => t.ln_1p() / 2f32.ln(),
+            VelocityCurve::SCurve => t * t * (3.0 - 2.0 * t),
+        }
+    }
 }
 
 fn main() {
     env_logger::init();
 
-    let settings = get_settings();
+    let cli = Cli::parse();
+
+    if let Some(CliCommand::ListPorts) = cli.command {
+        list_ports();
+        return;
+    }
+
+    let settings = Arc::new(get_settings(&cli.config));
 
     log::debug!("Settings: {:#?}", settings);
 
+    let device_pattern = Regex::new(&settings.device_port_name)
+        .expect("Invalid regular expression in 'device_port_name'");
+
+    let feedback_pattern = Regex::new(
+        settings
+            .feedback_port_name
+            .as_deref()
+            .unwrap_or(&settings.device_port_name),
+    )
+    .expect("Invalid regular expression in 'feedback_port_name'");
+
+    let debounce_state = Arc::new(Mutex::new(HashMap::<String, Instant>::new()));
+    let pulse_handle = pulse::spawn();
+
+    run_watcher(
+        settings,
+        device_pattern,
+        feedback_pattern,
+        debounce_state,
+        pulse_handle,
+        cli.dry_run,
+    );
+}
+
+/// Prints the name of every available MIDI input port, so users can find the exact
+/// string to match with `device_port_name`.
+fn list_ports() {
+    let midi_input = MidiInput::new("miditokeydaemon").expect("Failed to read MIDI input.");
+
+    for port in midi_input.ports() {
+        match midi_input.port_name(&port) {
+            Ok(name) => println!("{}", name),
+            Err(err) => log::error!("Failed to read port name: {:?}", err),
+        }
+    }
+}
+
+/// Finds the first port whose name matches `device_pattern`, enumerated from a fresh
+/// `MidiInput` instance (ports can't be enumerated from one that's already connected).
+fn find_matching_port(device_pattern: &Regex) -> Option<(MidiInput, midir::MidiInputPort)> {
     let midi_input = MidiInput::new("miditokeydaemon").expect("Failed to read MIDI input.");
 
-    let ports = midi_input.ports();
-    let port = ports.iter().find(|port| {
+    let matched_port = midi_input.ports().into_iter().find(|port| {
         let port_name = midi_input
             .port_name(port)
             .expect("Failed to read port name.");
 
         log::debug!("Port found: {:?}", port_name);
 
-        port_name.contains(&settings.device_port_name)
-    })
-    .expect("No MIDI ports available for the specified 'device_port_name' property in the configuration.");
+        device_pattern.is_match(&port_name)
+    })?;
+
+    Some((midi_input, matched_port))
+}
 
-    let port_name = midi_input.port_name(port).unwrap();
+/// Finds the first output port whose name matches `feedback_pattern`, mirroring
+/// `find_matching_port` for the output side.
+fn find_matching_output_port(feedback_pattern: &Regex) -> Option<(MidiOutput, midir::MidiOutputPort)> {
+    let midi_output = MidiOutput::new("miditokeydaemon").expect("Failed to read MIDI output.");
 
-    log::debug!("Selected MIDI Port: {}", port_name);
+    let matched_port = midi_output.ports().into_iter().find(|port| {
+        let port_name = midi_output
+            .port_name(port)
+            .expect("Failed to read port name.");
 
-    let debounce_state: HashMap<String, Instant> = HashMap::new();
+        feedback_pattern.is_match(&port_name)
+    })?;
 
-    let _connection = midi_input
-        .connect(
-            port,
-            port_name.as_str(),
-            move |timestamp, message, (settings, debounce_state)| {
-                log::debug!("[{}] Received MIDI message: {:?}", timestamp, message);
-                let _ = process_midi_message(message, settings, debounce_state);
-            },
-            (settings, debounce_state),
-        )
-        .expect("Failed to connect to MIDI input port");
+    Some((midi_output, matched_port))
+}
 
-    log::debug!("Daemon is initialized.");
+/// Keeps the daemon connected to the configured input and feedback output devices,
+/// re-enumerating ports on an interval instead of connecting once at startup. When a
+/// matched device disappears (unplugged, suspend/resume) or reappears, its connection is
+/// torn down and rebuilt transparently with the same `Settings` and `debounce_state`.
+fn run_watcher(
+    settings: Arc<Settings>,
+    device_pattern: Regex,
+    feedback_pattern: Regex,
+    debounce_state: Arc<Mutex<HashMap<String, Instant>>>,
+    pulse_handle: PulseHandle,
+    dry_run: bool,
+) {
+    let mut connection: Option<MidiInputConnection<()>> = None;
+    let feedback_connection: Arc<Mutex<Option<MidiOutputConnection>>> = Arc::new(Mutex::new(None));
 
     loop {
-        thread::sleep(Duration::from_millis(100));
+        match (&connection, find_matching_port(&device_pattern)) {
+            (Some(_), None) => {
+                log::info!("MIDI device disconnected, waiting for it to reappear.");
+                connection = None;
+            }
+            (None, Some((midi_input, port))) => {
+                let port_name = midi_input.port_name(&port).unwrap();
+
+                log::info!("MIDI device found: {}. Connecting...", port_name);
+
+                let settings = Arc::clone(&settings);
+                let debounce_state = Arc::clone(&debounce_state);
+                let pulse_handle = pulse_handle.clone();
+                let feedback_connection = Arc::clone(&feedback_connection);
+
+                let connect_result = midi_input.connect(
+                    &port,
+                    port_name.as_str(),
+                    move |timestamp, message, _| {
+                        log::debug!("[{}] Received MIDI message: {:?}", timestamp, message);
+                        let mut debounce_state = debounce_state.lock().unwrap();
+                        let _ = process_midi_message(
+                            message,
+                            &settings,
+                            &mut debounce_state,
+                            &pulse_handle,
+                            &feedback_connection,
+                            dry_run,
+                        );
+                    },
+                    (),
+                );
+
+                match connect_result {
+                    Ok(conn) => {
+                        log::debug!("Connected to MIDI port: {}", port_name);
+                        connection = Some(conn);
+                    }
+                    Err(err) => log::error!("Failed to connect to MIDI input port: {:?}", err),
+                }
+            }
+            _ => {}
+        }
+
+        {
+            let mut feedback_connection = feedback_connection.lock().unwrap();
+            match (&*feedback_connection, find_matching_output_port(&feedback_pattern)) {
+                (Some(_), None) => {
+                    log::info!("MIDI feedback device disconnected, waiting for it to reappear.");
+                    *feedback_connection = None;
+                }
+                (None, Some((midi_output, port))) => {
+                    let port_name = midi_output.port_name(&port).unwrap();
+
+                    log::info!("MIDI feedback port found: {}. Connecting...", port_name);
+
+                    match midi_output.connect(&port, port_name.as_str()) {
+                        Ok(conn) => {
+                            log::debug!("Connected to MIDI feedback port: {}", port_name);
+                            *feedback_connection = Some(conn);
+                        }
+                        Err(err) => {
+                            log::error!("Failed to connect to MIDI output port: {:?}", err)
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        thread::sleep(PORT_POLL_INTERVAL);
     }
 }
 
-/// This function reads the settings from the configuration file.
-fn get_settings() -> Settings {
-    let config_file_path = shellexpand::tilde("~/.miditokeydaemonrc");
+/// This function reads the settings from the configuration file at `config_path`.
+fn get_settings(config_path: &str) -> Settings {
+    let config_file_path = shellexpand::tilde(config_path);
 
     let config = Config::builder()
         .add_source(File::new(&config_file_path, FileFormat::Json))
@@ -101,12 +353,13 @@ fn get_settings() -> Settings {
         .expect("Failed to deserialize daemon settings.")
 }
 
-/// This function checks if the actual velocity matches the mapping velocity.
-/// If the mapping velocity is not specified, it returns true.
-fn match_velocity(velocity: Option<u8>, mapping: &MidiMap) -> bool {
-    mapping.velocity.map_or(true, |mapping_velocity| {
-        velocity.map_or(true, |actual_velocity| actual_velocity == mapping_velocity)
-    })
+/// This function checks if a parsed MIDI value falls within the mapping's `value_range`.
+/// If no range is specified, it returns true, matching any value (e.g. a fixed key press).
+fn match_value(value: u16, mapping: &MidiMap) -> bool {
+    mapping
+        .value_range
+        .as_ref()
+        .map_or(true, |range| value >= range.min && value <= range.max)
 }
 
 /// This function computes the velocity based on the mapping options.
@@ -115,7 +368,13 @@ fn get_computed_velocity(velocity: Option<u8>, mapping: &MidiMap) -> Option<u8>
     let velocity_scale = mapping.options.clone()?.velocity?.scale;
 
     match velocity_scale {
-        Some(scale) => Some(scale_value(velocity?, scale.min, scale.max)),
+        Some(scale) => Some(scale_value(
+            velocity?,
+            scale.min,
+            scale.max,
+            scale.curve.as_ref(),
+            scale.invert.unwrap_or(false),
+        )),
         None => velocity,
     }
 }
@@ -132,35 +391,54 @@ fn get_debounce_duration(mapping: &MidiMap) -> Duration {
 }
 
 /// This function processes a MIDI message based on the settings.
-/// It checks each mapping in the settings, and if the MIDI ID, note, and velocity match the mapping,
-/// it executes the associated action.
+/// It parses the raw bytes into a typed `MidiEvent`, then checks each mapping in the
+/// settings; if the event type, channel, note/controller, and value all match, it executes
+/// the associated action.
 fn process_midi_message(
     message: &[u8],
     settings: &Settings,
     debounce_state: &mut HashMap<String, Instant>,
+    pulse_handle: &PulseHandle,
+    feedback_connection: &Mutex<Option<MidiOutputConnection>>,
+    dry_run: bool,
 ) -> Result<(), anyhow::Error> {
-    let (midi_id, note, device_velocity) = (message[0], message[1], message.get(2).cloned());
+    let event = match MidiEvent::parse(message) {
+        Some(event) => event,
+        None => return Ok(()),
+    };
 
-    let mut enigo = Enigo::new();
+    let device_velocity = Some(event.velocity_byte());
+
+    // Constructed lazily, on the first matched keymap, so dry-run never touches enigo
+    // (opening an X11/uinput connection can panic on a headless host).
+    let mut enigo: Option<Enigo> = None;
 
     for mapping in &settings.midi_mapping {
-        let mapping_match = midi_id == mapping.midi_id
-            && note == mapping.note
-            && match_velocity(device_velocity, mapping);
+        let mapping_match = mapping.note.map_or(true, |note| note == event.data1)
+            && mapping.event_type.map_or(true, |t| t == event.event_type)
+            && mapping.channel.map_or(true, |channel| channel == event.channel)
+            && match_value(event.value, mapping);
 
         if !mapping_match {
             continue;
         }
 
         log::debug!(
-            "Found a midi_id '{}', note '{}' and velocity match.",
-            midi_id,
-            note
+            "Found a {:?}, note/controller '{}' and value match on channel {}.",
+            event.event_type,
+            event.data1,
+            event.channel
         );
 
+        if dry_run {
+            log::info!("[dry-run] Mapping would fire: {:?}", mapping);
+            continue;
+        }
+
         if let Some(keymap) = &mapping.keymap {
             log::debug!("Parsing keymap: {}", keymap);
-            if let Err(err) = enigo_dsl::eval(&mut enigo, keymap.as_str()) {
+            let enigo = enigo.get_or_insert_with(Enigo::new);
+            if let Err(err) = enigo_dsl::eval(enigo, keymap.as_str()) {
                 log::error!("Failed to parse keymap {}", keymap);
                 log::error!("{:?}", err);
             }
@@ -205,15 +483,101 @@ fn process_midi_message(
                 .spawn()
                 .expect(&err_message);
         }
+
+        if let Some(action) = &mapping.pulse {
+            if let Some(raw_velocity) = device_velocity {
+                // `get_computed_velocity` remaps into the mapping's `scale.min..scale.max`,
+                // which is meant for `$MIDI_VELOCITY`/commands; feeding that through a second
+                // 0..100 remap here would double-scale it. Go from the raw 0..127 device
+                // velocity straight to a percent instead, keeping only the curve/invert shaping.
+                let scale = mapping
+                    .options
+                    .clone()
+                    .and_then(|o| o.velocity)
+                    .and_then(|v| v.scale);
+                let percent = scale_value(
+                    raw_velocity,
+                    0,
+                    100,
+                    scale.as_ref().and_then(|s| s.curve.as_ref()),
+                    scale.as_ref().map_or(false, |s| s.invert.unwrap_or(false)),
+                );
+                pulse_handle.send(action.clone().into_command(percent));
+            }
+        }
+
+        if let Some(feedback) = &mapping.feedback {
+            let mut feedback_connection = feedback_connection.lock().unwrap();
+            if let Some(connection) = feedback_connection.as_mut() {
+                if let Err(err) = connection.send(&feedback.to_bytes()) {
+                    log::error!("Failed to send MIDI feedback: {:?}", err);
+                }
+            }
+        }
     }
 
     Ok(())
 }
 
-/// This function scales the input value to the specified range.
-fn scale_value(input: u8, min: u8, max: u8) -> u8 {
+/// This function scales the input value to the specified range, optionally reshaping the
+/// response curve and/or inverting the direction first.
+fn scale_value(input: u8, min: u8, max: u8, curve: Option<&VelocityCurve>, invert: bool) -> u8 {
+    let t = input as f32 / 127.0;
+    let t = curve.map_or(t, |curve| curve.apply(t));
+    let t = if invert { 1.0 - t } else { t };
+
     let range = max as f32 - min as f32;
-    let scale_factor = range / 127.0;
-    let output = min as f32 + ((input as f32) * scale_factor);
+    let output = min as f32 + (t * range);
     output.round() as u8
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_value_linear_spans_the_full_range() {
+        assert_eq!(scale_value(0, 0, 127, None, false), 0);
+        assert_eq!(scale_value(127, 0, 127, None, false), 127);
+        assert_eq!(scale_value(64, 0, 100, None, false), 50);
+    }
+
+    #[test]
+    fn scale_value_invert_flips_the_direction() {
+        assert_eq!(scale_value(0, 0, 100, None, true), 100);
+        assert_eq!(scale_value(127, 0, 100, None, true), 0);
+    }
+
+    #[test]
+    fn scale_value_exponential_curve_bows_toward_the_low_end() {
+        let curve = VelocityCurve::Exponential { gamma: 2.0 };
+        assert_eq!(scale_value(0, 0, 100, Some(&curve), false), 0);
+        assert_eq!(scale_value(127, 0, 100, Some(&curve), false), 100);
+        // Halfway input lands well below the linear midpoint once squared.
+        assert!(scale_value(64, 0, 100, Some(&curve), false) < 50);
+    }
+
+    #[test]
+    fn scale_value_logarithmic_curve_bows_toward_the_high_end() {
+        let curve = VelocityCurve::Logarithmic;
+        assert_eq!(scale_value(0, 0, 100, Some(&curve), false), 0);
+        assert_eq!(scale_value(127, 0, 100, Some(&curve), false), 100);
+        assert!(scale_value(64, 0, 100, Some(&curve), false) > 50);
+    }
+
+    #[test]
+    fn scale_value_s_curve_is_monotonic_and_hits_both_ends() {
+        assert_eq!(scale_value(0, 0, 100, Some(&VelocityCurve::SCurve), false), 0);
+        assert_eq!(
+            scale_value(127, 0, 100, Some(&VelocityCurve::SCurve), false),
+            100
+        );
+
+        let mut previous = 0;
+        for input in (0..=127).step_by(8) {
+            let output = scale_value(input, 0, 100, Some(&VelocityCurve::SCurve), false);
+            assert!(output >= previous);
+            previous = output;
+        }
+    }
+}
@@ -0,0 +1,133 @@
+/// The high-level category of a MIDI status byte, independent of channel.
+///
+/// The message type lives in the high nibble of the status byte and the channel in the low
+/// nibble; this only covers the message types the daemon knows how to act on.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiEventType {
+    NoteOff,
+    NoteOn,
+    ControlChange,
+    ProgramChange,
+    PitchBend,
+}
+
+impl MidiEventType {
+    fn from_status_nibble(nibble: u8) -> Option<Self> {
+        match nibble {
+            0x8 => Some(MidiEventType::NoteOff),
+            0x9 => Some(MidiEventType::NoteOn),
+            0xB => Some(MidiEventType::ControlChange),
+            0xC => Some(MidiEventType::ProgramChange),
+            0xE => Some(MidiEventType::PitchBend),
+            _ => None,
+        }
+    }
+}
+
+/// A MIDI message parsed from raw bytes, with the message type, channel, and data split out.
+///
+/// `data1` is the note number or controller number; `value` is the velocity or CC value
+/// (0..127) for `NoteOn`, `NoteOff` and `ControlChange`, and for `PitchBend` it's the
+/// combined 14-bit value (`data1 | (data2 << 7)`, range 0..16383, center 8192).
+/// `ProgramChange` carries a single data byte, so its program number is in `data1` and its
+/// `value` is always 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MidiEvent {
+    pub event_type: MidiEventType,
+    pub channel: u8,
+    pub data1: u8,
+    pub value: u16,
+}
+
+impl MidiEvent {
+    /// Parses a raw MIDI message into a typed event. Returns `None` for status bytes this
+    /// daemon doesn't act on (e.g. system messages) or for messages missing their first data
+    /// byte (or, for `PitchBend`, their second). Any other missing second data byte reads as
+    /// a `value` of 0.
+    pub fn parse(message: &[u8]) -> Option<MidiEvent> {
+        let status = *message.first()?;
+        let event_type = MidiEventType::from_status_nibble(status >> 4)?;
+        let channel = status & 0x0F;
+        let data1 = *message.get(1)?;
+
+        let value = match event_type {
+            MidiEventType::PitchBend => {
+                let data2 = *message.get(2)? as u16;
+                data1 as u16 | (data2 << 7)
+            }
+            _ => message.get(2).copied().unwrap_or(0) as u16,
+        };
+
+        Some(MidiEvent {
+            event_type,
+            channel,
+            data1,
+            value,
+        })
+    }
+
+    /// Collapses `value` back down to a single byte for callers that only deal in 0..127
+    /// velocities, such as `scale_value` and the `$MIDI_VELOCITY` env var.
+    pub fn velocity_byte(&self) -> u8 {
+        match self.event_type {
+            MidiEventType::PitchBend => (self.value >> 7) as u8,
+            _ => self.value as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_note_on_with_channel() {
+        let event = MidiEvent::parse(&[0x91, 60, 100]).unwrap();
+        assert_eq!(event.event_type, MidiEventType::NoteOn);
+        assert_eq!(event.channel, 1);
+        assert_eq!(event.data1, 60);
+        assert_eq!(event.value, 100);
+    }
+
+    #[test]
+    fn parses_control_change_value() {
+        let event = MidiEvent::parse(&[0xB3, 7, 64]).unwrap();
+        assert_eq!(event.event_type, MidiEventType::ControlChange);
+        assert_eq!(event.channel, 3);
+        assert_eq!(event.data1, 7);
+        assert_eq!(event.value, 64);
+    }
+
+    #[test]
+    fn rejects_unknown_status_and_truncated_messages() {
+        assert!(MidiEvent::parse(&[0xF8]).is_none());
+        assert!(MidiEvent::parse(&[0x90]).is_none());
+        assert!(MidiEvent::parse(&[]).is_none());
+    }
+
+    #[test]
+    fn pitch_bend_reassembles_14_bit_value() {
+        // Minimum, center, and maximum of the 14-bit pitch-bend range.
+        assert_eq!(MidiEvent::parse(&[0xE0, 0x00, 0x00]).unwrap().value, 0);
+        assert_eq!(MidiEvent::parse(&[0xE0, 0x00, 0x40]).unwrap().value, 8192);
+        assert_eq!(
+            MidiEvent::parse(&[0xE0, 0x7F, 0x7F]).unwrap().value,
+            16383
+        );
+    }
+
+    #[test]
+    fn velocity_byte_round_trips_pitch_bend_at_key_points() {
+        for (data1, data2, expected) in [(0x00, 0x00, 0), (0x00, 0x40, 64), (0x7F, 0x7F, 127)] {
+            let event = MidiEvent::parse(&[0xE0, data1, data2]).unwrap();
+            assert_eq!(event.velocity_byte(), expected);
+        }
+    }
+
+    #[test]
+    fn velocity_byte_passes_through_non_pitch_bend_value() {
+        let event = MidiEvent::parse(&[0x92, 60, 42]).unwrap();
+        assert_eq!(event.velocity_byte(), 42);
+    }
+}
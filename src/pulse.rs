@@ -0,0 +1,288 @@
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use libpulse_binding as pulse;
+use pulse::context::introspect::{Introspector, ListResult, SinkInfo, SourceInfo};
+use pulse::context::{Context, FlagSet as ContextFlagSet, State as ContextState};
+use pulse::mainloop::threaded::Mainloop;
+use pulse::proplist::Proplist;
+use pulse::volume::{ChannelVolumes, Volume};
+
+/// How long to wait for a sink/source info query to come back before falling back to a
+/// safe default. Queries are answered on the PulseAudio mainloop thread via a callback.
+const INTROSPECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Identifies the PulseAudio object a mapping controls, by name or by index.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PulseTarget {
+    Name(String),
+    Index(u32),
+}
+
+/// A PulseAudio action a mapping can trigger, alongside `keymap`/`command`/`mouse`.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PulseAction {
+    SinkVolume { target: PulseTarget },
+    SinkMuteToggle { target: PulseTarget },
+    SourceVolume { target: PulseTarget },
+    SourceMuteToggle { target: PulseTarget },
+    SetDefaultSink { target: PulseTarget },
+}
+
+impl PulseAction {
+    /// Turns this action plus a computed velocity into the command sent to the worker thread.
+    pub fn into_command(self, percent: u8) -> PulseCommand {
+        match self {
+            PulseAction::SinkVolume { target } => PulseCommand::SetSinkVolume { target, percent },
+            PulseAction::SinkMuteToggle { target } => PulseCommand::ToggleSinkMute { target },
+            PulseAction::SourceVolume { target } => {
+                PulseCommand::SetSourceVolume { target, percent }
+            }
+            PulseAction::SourceMuteToggle { target } => PulseCommand::ToggleSourceMute { target },
+            PulseAction::SetDefaultSink { target } => PulseCommand::SetDefaultSink { target },
+        }
+    }
+}
+
+/// A command sent to the PulseAudio worker thread.
+#[derive(Debug)]
+pub enum PulseCommand {
+    SetSinkVolume { target: PulseTarget, percent: u8 },
+    ToggleSinkMute { target: PulseTarget },
+    SetSourceVolume { target: PulseTarget, percent: u8 },
+    ToggleSourceMute { target: PulseTarget },
+    SetDefaultSink { target: PulseTarget },
+}
+
+/// A handle to the PulseAudio worker thread, used to queue commands from the MIDI
+/// callback thread without spawning a `pactl` subprocess on every knob tick.
+#[derive(Clone)]
+pub struct PulseHandle {
+    sender: Sender<PulseCommand>,
+}
+
+impl PulseHandle {
+    pub fn send(&self, command: PulseCommand) {
+        if let Err(err) = self.sender.send(command) {
+            log::error!("Failed to queue PulseAudio command: {:?}", err);
+        }
+    }
+}
+
+/// Spawns the PulseAudio client on a dedicated thread and returns a handle to send it
+/// commands. The client connects once and then applies commands as they arrive.
+pub fn spawn() -> PulseHandle {
+    let (sender, receiver) = mpsc::channel::<PulseCommand>();
+
+    thread::spawn(move || {
+        let mut proplist = Proplist::new().expect("Failed to create PulseAudio proplist");
+        proplist
+            .set_str(
+                pulse::proplist::properties::APPLICATION_NAME,
+                "miditokeydaemon",
+            )
+            .expect("Failed to set PulseAudio application name");
+
+        let mainloop = Mainloop::new().expect("Failed to create PulseAudio mainloop");
+        let context = Context::new_with_proplist(&mainloop, "miditokeydaemonContext", &proplist)
+            .expect("Failed to create PulseAudio context");
+
+        let mainloop = Arc::new(Mutex::new(mainloop));
+        let context = Arc::new(Mutex::new(context));
+
+        {
+            let mut mainloop = mainloop.lock().unwrap();
+            let mut ctx = context.lock().unwrap();
+
+            ctx.connect(None, ContextFlagSet::NOFLAGS, None)
+                .expect("Failed to connect to the PulseAudio server");
+
+            mainloop.start().expect("Failed to start PulseAudio mainloop");
+
+            while !matches!(ctx.get_state(), ContextState::Ready) {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+        }
+
+        for command in receiver {
+            log::debug!("Applying PulseAudio command: {:?}", command);
+            let ctx = context.lock().unwrap();
+            apply_command(&mainloop, &ctx, command);
+        }
+    });
+
+    PulseHandle { sender }
+}
+
+/// Runs `f` while holding PulseAudio's own mainloop lock, serializing the context/introspector
+/// call it makes against the dispatch thread that `mainloop.start()` spawned. `f` must only
+/// *enqueue* work (a query callback, a `set_*` call) and return immediately — never block
+/// inside it, since the dispatch thread needs this same lock free to run callbacks.
+fn with_mainloop_lock<T>(mainloop: &Mutex<Mainloop>, f: impl FnOnce() -> T) -> T {
+    let mut guard = mainloop.lock().unwrap();
+    guard.lock();
+    let result = f();
+    guard.unlock();
+    result
+}
+
+fn apply_command(mainloop: &Mutex<Mainloop>, context: &Context, command: PulseCommand) {
+    let mut introspector = context.introspect();
+
+    match command {
+        PulseCommand::SetSinkVolume { target, percent } => {
+            let channels = query_sink_channel_count(mainloop, &introspector, &target);
+            let volume = percent_to_channel_volumes(percent, channels);
+            with_mainloop_lock(mainloop, || match &target {
+                PulseTarget::Name(name) => {
+                    introspector.set_sink_volume_by_name(name, &volume, None);
+                }
+                PulseTarget::Index(index) => {
+                    introspector.set_sink_volume_by_index(*index, &volume, None);
+                }
+            });
+        }
+        PulseCommand::ToggleSinkMute { target } => {
+            let currently_muted = query_sink_mute(mainloop, &introspector, &target);
+            with_mainloop_lock(mainloop, || match &target {
+                PulseTarget::Name(name) => {
+                    introspector.set_sink_mute_by_name(name, !currently_muted, None);
+                }
+                PulseTarget::Index(index) => {
+                    introspector.set_sink_mute_by_index(*index, !currently_muted, None);
+                }
+            });
+        }
+        PulseCommand::SetSourceVolume { target, percent } => {
+            let channels = query_source_channel_count(mainloop, &introspector, &target);
+            let volume = percent_to_channel_volumes(percent, channels);
+            with_mainloop_lock(mainloop, || match &target {
+                PulseTarget::Name(name) => {
+                    introspector.set_source_volume_by_name(name, &volume, None);
+                }
+                PulseTarget::Index(index) => {
+                    introspector.set_source_volume_by_index(*index, &volume, None);
+                }
+            });
+        }
+        PulseCommand::ToggleSourceMute { target } => {
+            let currently_muted = query_source_mute(mainloop, &introspector, &target);
+            with_mainloop_lock(mainloop, || match &target {
+                PulseTarget::Name(name) => {
+                    introspector.set_source_mute_by_name(name, !currently_muted, None);
+                }
+                PulseTarget::Index(index) => {
+                    introspector.set_source_mute_by_index(*index, !currently_muted, None);
+                }
+            });
+        }
+        PulseCommand::SetDefaultSink { target } => {
+            if let PulseTarget::Name(name) = target {
+                with_mainloop_lock(mainloop, || context.set_default_sink(&name, |_| {}));
+            } else {
+                log::warn!("'set_default_sink' requires a sink name, not an index.");
+            }
+        }
+    };
+}
+
+/// Blocks on a single introspection query, returning `fallback` if the server doesn't
+/// answer within `INTROSPECT_TIMEOUT` (e.g. the target doesn't exist). This must run
+/// *without* the mainloop lock held, since the answer is delivered by a callback that
+/// runs on PulseAudio's dispatch thread, which needs that same lock to deliver it.
+fn recv_introspect_result<T>(receiver: mpsc::Receiver<T>, fallback: T) -> T {
+    receiver.recv_timeout(INTROSPECT_TIMEOUT).unwrap_or(fallback)
+}
+
+fn query_sink_mute(
+    mainloop: &Mutex<Mainloop>,
+    introspector: &Introspector,
+    target: &PulseTarget,
+) -> bool {
+    let (sender, receiver) = mpsc::channel::<bool>();
+    let callback = move |result: ListResult<&SinkInfo>| {
+        if let ListResult::Item(info) = result {
+            let _ = sender.send(info.mute);
+        }
+    };
+
+    with_mainloop_lock(mainloop, || match target {
+        PulseTarget::Name(name) => introspector.get_sink_info_by_name(name, callback),
+        PulseTarget::Index(index) => introspector.get_sink_info_by_index(*index, callback),
+    });
+
+    recv_introspect_result(receiver, false)
+}
+
+fn query_sink_channel_count(
+    mainloop: &Mutex<Mainloop>,
+    introspector: &Introspector,
+    target: &PulseTarget,
+) -> u8 {
+    let (sender, receiver) = mpsc::channel::<u8>();
+    let callback = move |result: ListResult<&SinkInfo>| {
+        if let ListResult::Item(info) = result {
+            let _ = sender.send(info.volume.len());
+        }
+    };
+
+    with_mainloop_lock(mainloop, || match target {
+        PulseTarget::Name(name) => introspector.get_sink_info_by_name(name, callback),
+        PulseTarget::Index(index) => introspector.get_sink_info_by_index(*index, callback),
+    });
+
+    recv_introspect_result(receiver, 2)
+}
+
+fn query_source_mute(
+    mainloop: &Mutex<Mainloop>,
+    introspector: &Introspector,
+    target: &PulseTarget,
+) -> bool {
+    let (sender, receiver) = mpsc::channel::<bool>();
+    let callback = move |result: ListResult<&SourceInfo>| {
+        if let ListResult::Item(info) = result {
+            let _ = sender.send(info.mute);
+        }
+    };
+
+    with_mainloop_lock(mainloop, || match target {
+        PulseTarget::Name(name) => introspector.get_source_info_by_name(name, callback),
+        PulseTarget::Index(index) => introspector.get_source_info_by_index(*index, callback),
+    });
+
+    recv_introspect_result(receiver, false)
+}
+
+fn query_source_channel_count(
+    mainloop: &Mutex<Mainloop>,
+    introspector: &Introspector,
+    target: &PulseTarget,
+) -> u8 {
+    let (sender, receiver) = mpsc::channel::<u8>();
+    let callback = move |result: ListResult<&SourceInfo>| {
+        if let ListResult::Item(info) = result {
+            let _ = sender.send(info.volume.len());
+        }
+    };
+
+    with_mainloop_lock(mainloop, || match target {
+        PulseTarget::Name(name) => introspector.get_source_info_by_name(name, callback),
+        PulseTarget::Index(index) => introspector.get_source_info_by_index(*index, callback),
+    });
+
+    recv_introspect_result(receiver, 2)
+}
+
+fn percent_to_channel_volumes(percent: u8, channels: u8) -> ChannelVolumes {
+    let mut volume = Volume::NORMAL;
+    volume.0 = ((Volume::NORMAL.0 as f32) * (percent.min(100) as f32 / 100.0)) as u32;
+
+    let mut channel_volumes = ChannelVolumes::default();
+    channel_volumes.set(channels.max(1), volume);
+    channel_volumes
+}